@@ -1,5 +1,5 @@
 /// A single state in the state machine used by `unescape`.
-#[derive(Clone, Copy, Eq, PartialEq)]
+#[derive(Clone, Eq, PartialEq)]
 enum State {
     /// The state after seeing a `\`.
     Escape,
@@ -7,29 +7,143 @@ enum State {
     HexFirst,
     /// The state after seeing a `\x[0-9A-Fa-f]`.
     HexSecond(char),
+    /// The state after seeing a `\u`, expecting a `{` next.
+    UnicodeBrace,
+    /// The state after seeing `\u{` followed by zero or more hex digits.
+    UnicodeDigits(String),
     /// Default state.
     Literal,
 }
 
+/// The kind of error reported by `unescape_checked`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum UnescapeErrorKind {
+    /// A `\x` escape ended before two hex digits were seen, because the
+    /// input ended too soon.
+    TooShortHex,
+    /// A `\x` escape contained a character, in either digit position,
+    /// that is not a valid hex digit.
+    InvalidHexDigit,
+    /// A `\` appeared as the very last character of the input.
+    LoneBackslashAtEnd,
+    /// A `\` was followed by a character that does not begin any known
+    /// escape sequence, e.g. `\q`.
+    UnrecognizedEscape,
+    /// A `\u{...}` escape was malformed: missing `{` or `}`, no hex
+    /// digits, too many hex digits, or digits that don't form a legal
+    /// Unicode scalar value.
+    InvalidCodepoint,
+}
+
+/// An error that occurs when `unescape_checked` encounters a malformed
+/// escape sequence.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnescapeError {
+    offset: usize,
+    kind: UnescapeErrorKind,
+}
+
+impl UnescapeError {
+    fn new(offset: usize, kind: UnescapeErrorKind) -> UnescapeError {
+        UnescapeError { offset, kind }
+    }
+
+    /// The char offset, within the original input, of the `\` that starts
+    /// the offending escape sequence.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The kind of error that occurred.
+    pub fn kind(&self) -> &UnescapeErrorKind {
+        &self.kind
+    }
+}
+
+impl ::std::fmt::Display for UnescapeError {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        use self::UnescapeErrorKind::*;
+
+        let msg = match self.kind {
+            TooShortHex => "incomplete \\x escape",
+            InvalidHexDigit => "invalid hex digit in \\x escape",
+            LoneBackslashAtEnd => "incomplete escape sequence",
+            UnrecognizedEscape => "unrecognized escape sequence",
+            InvalidCodepoint => "invalid \\u{...} escape",
+        };
+        write!(f, "{} at position {}", msg, self.offset)
+    }
+}
+
+impl ::std::error::Error for UnescapeError {
+    fn description(&self) -> &str {
+        "invalid escape sequence"
+    }
+}
+
 /// Unescapes a string given on the command line. It supports a limited set of
 /// escape sequences:
 ///
-/// * \t, \r and \n are mapped to their corresponding ASCII bytes.
+/// * \0, \t, \r and \n are mapped to their corresponding ASCII bytes.
+/// * \\ is mapped to a single literal backslash.
 /// * \xZZ hexadecimal escapes are mapped to their byte.
+/// * \u{...} escapes, with 1-6 hex digits, are mapped to the UTF-8 encoding
+///   of the corresponding Unicode scalar value.
+///
+/// Any malformed escape sequence is passed through to the result as
+/// literal text. Use `unescape_checked` if you'd like to be told about
+/// malformed escape sequences instead.
 pub fn unescape(s: &str) -> Vec<u8> {
+    unescape_impl(s, false).unwrap()
+}
+
+/// Like `unescape`, but reports an error, including the char offset of the
+/// offending `\`, instead of silently passing malformed escape sequences
+/// through as literal text.
+///
+/// BLOCKED: the request behind this function also asked for the CLI layer
+/// that parses `--replace`/pattern strings to call this instead of
+/// `unescape`, so typos are reported with a diagnostic pointing at
+/// `offset()`. That CLI/argument-parsing code is not present anywhere in
+/// this source tree (there is no `app.rs`, no `grep-cli`, nothing outside
+/// this file that calls `unescape` at all), so that half of the request
+/// is out of scope here and unresolved, not done. Wire it up once that
+/// code exists in this tree.
+pub fn unescape_checked(s: &str) -> Result<Vec<u8>, UnescapeError> {
+    unescape_impl(s, true)
+}
+
+/// The shared implementation behind `unescape` and `unescape_checked`.
+///
+/// When `strict` is `false`, malformed escape sequences are always pushed
+/// onto the result as literal text and this function always returns `Ok`.
+/// When `strict` is `true`, the first malformed escape sequence instead
+/// causes this function to return `Err`.
+fn unescape_impl(s: &str, strict: bool) -> Result<Vec<u8>, UnescapeError> {
     use self::State::*;
+    use self::UnescapeErrorKind::*;
 
     let mut bytes = vec![];
     let mut state = Literal;
-    for c in s.chars() {
+    // The char offset of the `\` that started the escape sequence
+    // currently being parsed, i.e. the state of `state` above.
+    let mut escape_start = 0;
+    for (i, c) in s.chars().enumerate() {
         match state {
             Escape => {
                 match c {
+                    '0' => { bytes.push(b'\0'); state = Literal; }
                     'n' => { bytes.push(b'\n'); state = Literal; }
                     'r' => { bytes.push(b'\r'); state = Literal; }
                     't' => { bytes.push(b'\t'); state = Literal; }
+                    '\\' => { bytes.push(b'\\'); state = Literal; }
                     'x' => { state = HexFirst; }
+                    'u' => { state = UnicodeBrace; }
                     c => {
+                        if strict {
+                            return Err(UnescapeError::new(
+                                escape_start, UnrecognizedEscape));
+                        }
                         bytes.extend(format!(r"\{}", c).into_bytes());
                         state = Literal;
                     }
@@ -41,6 +155,10 @@ pub fn unescape(s: &str) -> Vec<u8> {
                         state = HexSecond(c);
                     }
                     c => {
+                        if strict {
+                            return Err(UnescapeError::new(
+                                escape_start, InvalidHexDigit));
+                        }
                         bytes.extend(format!(r"\x{}", c).into_bytes());
                         state = Literal;
                     }
@@ -55,32 +173,210 @@ pub fn unescape(s: &str) -> Vec<u8> {
                         state = Literal;
                     }
                     c => {
+                        if strict {
+                            return Err(UnescapeError::new(
+                                escape_start, InvalidHexDigit));
+                        }
                         let original = format!(r"\x{}{}", first, c);
                         bytes.extend(original.into_bytes());
                         state = Literal;
                     }
                 }
             }
+            UnicodeBrace => {
+                match c {
+                    '{' => { state = UnicodeDigits(String::new()); }
+                    c => {
+                        if strict {
+                            return Err(UnescapeError::new(
+                                escape_start, InvalidCodepoint));
+                        }
+                        bytes.extend(format!(r"\u{}", c).into_bytes());
+                        state = Literal;
+                    }
+                }
+            }
+            UnicodeDigits(mut digits) => {
+                match c {
+                    '0'...'9' | 'A'...'F' | 'a'...'f'
+                    if digits.len() < 6 => {
+                        digits.push(c);
+                        state = UnicodeDigits(digits);
+                    }
+                    '}' if !digits.is_empty() => {
+                        if strict {
+                            match parse_unicode_scalar(&digits) {
+                                Some(ch) => {
+                                    let mut buf = [0; 4];
+                                    bytes.extend(
+                                        ch.encode_utf8(&mut buf).as_bytes());
+                                }
+                                None => {
+                                    return Err(UnescapeError::new(
+                                        escape_start, InvalidCodepoint));
+                                }
+                            }
+                        } else {
+                            push_unicode_scalar(&mut bytes, &digits);
+                        }
+                        state = Literal;
+                    }
+                    // Malformed: too many digits, or some other character
+                    // where a digit or a closing brace was expected. Flush
+                    // what we've matched so far, plus `c`, as literal text.
+                    // As with the `HexFirst`/`HexSecond`/`UnicodeBrace`
+                    // arms above, we never restart the escape machine on
+                    // malformed input, even if `c` is itself a `\`.
+                    _ => {
+                        if strict {
+                            return Err(UnescapeError::new(
+                                escape_start, InvalidCodepoint));
+                        }
+                        bytes.extend(
+                            format!(r"\u{{{}{}", digits, c).into_bytes());
+                        state = Literal;
+                    }
+                }
+            }
             Literal => {
                 match c {
-                    '\\' => { state = Escape; }
+                    '\\' => { escape_start = i; state = Escape; }
                     c => { bytes.extend(c.to_string().as_bytes()); }
                 }
             }
         }
     }
     match state {
-        Escape => bytes.push(b'\\'),
-        HexFirst => bytes.extend(b"\\x"),
-        HexSecond(c) => bytes.extend(format!("\\x{}", c).into_bytes()),
+        Escape => {
+            if strict {
+                return Err(UnescapeError::new(
+                    escape_start, LoneBackslashAtEnd));
+            }
+            bytes.push(b'\\');
+        }
+        HexFirst => {
+            if strict {
+                return Err(UnescapeError::new(escape_start, TooShortHex));
+            }
+            bytes.extend(b"\\x");
+        }
+        HexSecond(c) => {
+            if strict {
+                return Err(UnescapeError::new(escape_start, TooShortHex));
+            }
+            bytes.extend(format!("\\x{}", c).into_bytes());
+        }
+        UnicodeBrace => {
+            if strict {
+                return Err(UnescapeError::new(
+                    escape_start, InvalidCodepoint));
+            }
+            bytes.extend(b"\\u");
+        }
+        UnicodeDigits(digits) => {
+            if strict {
+                return Err(UnescapeError::new(
+                    escape_start, InvalidCodepoint));
+            }
+            bytes.extend(format!("\\u{{{}", digits).into_bytes());
+        }
         Literal => {}
     }
-    bytes
+    Ok(bytes)
+}
+
+/// Parses `digits` (1-6 hex digits) as a Unicode scalar value, returning
+/// `None` if `digits` does not form a legal Unicode scalar value (e.g.
+/// it's in the surrogate range, or too big).
+fn parse_unicode_scalar(digits: &str) -> Option<char> {
+    u32::from_str_radix(digits, 16).ok().and_then(::std::char::from_u32)
+}
+
+/// Parses `digits` (1-6 hex digits) as a Unicode scalar value and pushes its
+/// UTF-8 encoding onto `bytes`. If `digits` does not form a legal Unicode
+/// scalar value, the original `\u{...}` text is pushed onto `bytes`
+/// instead, unchanged.
+fn push_unicode_scalar(bytes: &mut Vec<u8>, digits: &str) {
+    match parse_unicode_scalar(digits) {
+        Some(c) => {
+            let mut buf = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+        }
+        None => {
+            bytes.extend(format!(r"\u{{{}}}", digits).into_bytes());
+        }
+    }
+}
+
+/// Escapes arbitrary bytes into a printable, UTF-8 string that can be fed
+/// back into `unescape` to recover the original bytes.
+///
+/// * Valid, non-ASCII UTF-8 sequences are passed through unchanged.
+/// * Printable ASCII (`0x21..=0x7E`), other than `\`, is passed through
+///   unchanged.
+/// * `\0`, `\n`, `\r`, `\t` and `\\` are mapped to their two-character
+///   backslash forms.
+/// * Everything else — control characters and invalid UTF-8 alike — is
+///   emitted as a `\xZZ` hexadecimal escape.
+pub fn escape(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len());
+    let mut bytes = bytes;
+    while !bytes.is_empty() {
+        match ::std::str::from_utf8(bytes) {
+            Ok(all) => {
+                for c in all.chars() {
+                    escape_char(c, &mut s);
+                }
+                bytes = &bytes[bytes.len()..];
+            }
+            Err(err) => {
+                let valid_up_to = err.valid_up_to();
+                if valid_up_to > 0 {
+                    let s_valid = unsafe {
+                        ::std::str::from_utf8_unchecked(&bytes[..valid_up_to])
+                    };
+                    for c in s_valid.chars() {
+                        escape_char(c, &mut s);
+                    }
+                    bytes = &bytes[valid_up_to..];
+                } else {
+                    escape_byte(bytes[0], &mut s);
+                    bytes = &bytes[1..];
+                }
+            }
+        }
+    }
+    s
+}
+
+/// Escapes a single Unicode scalar value into `s`.
+fn escape_char(c: char, s: &mut String) {
+    if c.is_ascii() {
+        escape_byte(c as u8, s);
+    } else {
+        s.push(c);
+    }
+}
+
+/// Escapes a single byte, assumed to either be ASCII or part of an invalid
+/// UTF-8 sequence, into `s`.
+fn escape_byte(b: u8, s: &mut String) {
+    match b {
+        b'\0' => s.push_str(r"\0"),
+        b'\n' => s.push_str(r"\n"),
+        b'\r' => s.push_str(r"\r"),
+        b'\t' => s.push_str(r"\t"),
+        b'\\' => s.push_str(r"\\"),
+        0x21...0x7E => s.push(b as char),
+        _ => s.push_str(&format!(r"\x{:02x}", b)),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::unescape;
+    use super::{
+        escape, unescape, unescape_checked, UnescapeErrorKind,
+    };
 
     fn b(bytes: &'static [u8]) -> Vec<u8> {
         bytes.to_vec()
@@ -91,6 +387,16 @@ mod tests {
         assert_eq!(b(b"\x00"), unescape(r"\x00"));
     }
 
+    #[test]
+    fn unescape_nul_short() {
+        assert_eq!(b(b"\x00"), unescape(r"\0"));
+    }
+
+    #[test]
+    fn unescape_backslash() {
+        assert_eq!(b(b"\\"), unescape(r"\\"));
+    }
+
     #[test]
     fn unescape_nl() {
         assert_eq!(b(b"\n"), unescape(r"\n"));
@@ -125,4 +431,160 @@ mod tests {
     fn unescape_nothing_hex2() {
         assert_eq!(b(b"\\xzz"), unescape(r"\xzz"));
     }
+
+    #[test]
+    fn unescape_unicode_scalar() {
+        assert_eq!(b("☃".as_bytes()), unescape(r"\u{2603}"));
+    }
+
+    #[test]
+    fn unescape_unicode_scalar_short() {
+        assert_eq!(b(b"\0"), unescape(r"\u{0}"));
+    }
+
+    #[test]
+    fn unescape_unicode_scalar_emoji() {
+        assert_eq!(b("🙂".as_bytes()), unescape(r"\u{1F642}"));
+    }
+
+    #[test]
+    fn unescape_nothing_unicode_no_brace() {
+        assert_eq!(b(b"\\uz"), unescape(r"\uz"));
+    }
+
+    #[test]
+    fn unescape_nothing_unicode_no_digits() {
+        assert_eq!(b(b"\\u{}"), unescape(r"\u{}"));
+    }
+
+    #[test]
+    fn unescape_nothing_unicode_unterminated() {
+        assert_eq!(b(b"\\u{26"), unescape(r"\u{26"));
+    }
+
+    #[test]
+    fn unescape_nothing_unicode_surrogate() {
+        assert_eq!(b(b"\\u{d800}"), unescape(r"\u{d800}"));
+    }
+
+    #[test]
+    fn unescape_nothing_unicode_too_big() {
+        assert_eq!(b(b"\\u{110000}"), unescape(r"\u{110000}"));
+    }
+
+    #[test]
+    fn unescape_nothing_unicode_too_many_digits() {
+        assert_eq!(b(b"\\u{1000000}"), unescape(r"\u{1000000}"));
+    }
+
+    #[test]
+    fn unescape_nothing_unicode_malformed_never_restarts_escape() {
+        // A malformed `\u{...}` never restarts the escape machine on a
+        // trailing `\`, even if that `\` begins what looks like a valid
+        // escape of its own. This matches the `\u` (no brace) case below,
+        // and the pre-existing `\x` recovery arms.
+        assert_eq!(b(b"\\u{26\\x41}"), unescape(r"\u{26\x41}"));
+        assert_eq!(b(b"\\u\\x41"), unescape(r"\u\x41"));
+    }
+
+    #[test]
+    fn escape_printable_ascii() {
+        assert_eq!("abcXYZ", escape(b"abcXYZ"));
+    }
+
+    #[test]
+    fn escape_control_bytes() {
+        assert_eq!(r"\0\x01\x1f", escape(b"\x00\x01\x1f"));
+    }
+
+    #[test]
+    fn escape_backslash_and_friends() {
+        assert_eq!(r"\0\n\r\t\\", escape(b"\0\n\r\t\\"));
+    }
+
+    #[test]
+    fn escape_utf8_passthrough() {
+        assert_eq!("☃", escape("☃".as_bytes()));
+    }
+
+    #[test]
+    fn escape_invalid_utf8() {
+        assert_eq!(r"\xff\xfe", escape(&[0xFF, 0xFE]));
+    }
+
+    #[test]
+    fn escape_unescape_roundtrip_every_byte() {
+        for b in 0u8..=255 {
+            let bytes = vec![b];
+            assert_eq!(bytes, unescape(&escape(&bytes)), "byte {:#x}", b);
+        }
+    }
+
+    #[test]
+    fn escape_unescape_roundtrip_mixed() {
+        let cases: &[&[u8]] = &[
+            b"",
+            b"hello world",
+            b"\0\n\r\t\\",
+            "☃🙂".as_bytes(),
+            &[0xFF, 0xFE, b'a', 0x00, 0x7F],
+            &(0u8..=255).collect::<Vec<u8>>(),
+        ];
+        for bytes in cases {
+            assert_eq!(bytes.to_vec(), unescape(&escape(bytes)));
+        }
+    }
+
+    #[test]
+    fn unescape_checked_ok() {
+        assert_eq!(
+            b(b"\0\t\n\r\\"),
+            unescape_checked(r"\0\t\n\r\\").unwrap()
+        );
+        assert_eq!(
+            b("\t\n\r☃".as_bytes()),
+            unescape_checked(r"\t\n\r☃").unwrap()
+        );
+    }
+
+    #[test]
+    fn unescape_checked_lone_backslash() {
+        let err = unescape_checked(r"a\").unwrap_err();
+        assert_eq!(1, err.offset());
+        assert_eq!(&UnescapeErrorKind::LoneBackslashAtEnd, err.kind());
+    }
+
+    #[test]
+    fn unescape_checked_unrecognized_escape() {
+        let err = unescape_checked(r"a\qb").unwrap_err();
+        assert_eq!(1, err.offset());
+        assert_eq!(&UnescapeErrorKind::UnrecognizedEscape, err.kind());
+    }
+
+    #[test]
+    fn unescape_checked_too_short_hex() {
+        let err = unescape_checked(r"\x").unwrap_err();
+        assert_eq!(0, err.offset());
+        assert_eq!(&UnescapeErrorKind::TooShortHex, err.kind());
+    }
+
+    #[test]
+    fn unescape_checked_invalid_hex_digit() {
+        let err = unescape_checked(r"\xzz").unwrap_err();
+        assert_eq!(0, err.offset());
+        assert_eq!(&UnescapeErrorKind::InvalidHexDigit, err.kind());
+    }
+
+    #[test]
+    fn unescape_checked_invalid_codepoint() {
+        let err = unescape_checked(r"\u{d800}").unwrap_err();
+        assert_eq!(0, err.offset());
+        assert_eq!(&UnescapeErrorKind::InvalidCodepoint, err.kind());
+    }
+
+    #[test]
+    fn unescape_checked_offset_points_at_backslash() {
+        let err = unescape_checked(r"ab\xg").unwrap_err();
+        assert_eq!(2, err.offset());
+    }
 }